@@ -1,3 +1,5 @@
+use std::env;
+use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
@@ -23,8 +25,88 @@ impl Dir {
 #[derive(Clone, Copy, Debug)]
 struct Pipe(Dir, Dir);
 
+impl Pipe {
+    fn opens(self, d: Dir) -> bool {
+        self.0 == d || self.1 == d
+    }
+
+    fn ascii_char(self) -> char {
+        match (self.opens(Dir::N), self.opens(Dir::S), self.opens(Dir::E)) {
+            (true, true, _) => '|',
+            (true, false, true) => 'L',
+            (true, false, false) => 'J',
+            (false, true, true) => 'F',
+            (false, true, false) => '7',
+            (false, false, _) => '-',
+        }
+    }
+
+    fn box_char(self) -> char {
+        match (self.opens(Dir::N), self.opens(Dir::S), self.opens(Dir::E)) {
+            (true, true, _) => '│',
+            (true, false, true) => '└',
+            (true, false, false) => '┘',
+            (false, true, true) => '┌',
+            (false, true, false) => '┐',
+            (false, false, _) => '─',
+        }
+    }
+}
+
 type Grid = Vec<Vec<Option<Pipe>>>;
 
+/// One tile of the loop and the direction taken out of it.
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    pos: (usize, usize),
+    dir: Dir,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tile {
+    Loop,
+    Inside,
+    Outside,
+}
+
+/// Classifies every tile against the loop by scanline parity: moving along a row, we cross
+/// the loop at each loop tile that opens north.
+fn classify(g: &Grid, path: &[Step]) -> Vec<Vec<Tile>> {
+    let mut tiles: Vec<Vec<Tile>> = g.iter().map(|row| vec![Tile::Outside; row.len()]).collect();
+    for step in path {
+        tiles[step.pos.0][step.pos.1] = Tile::Loop;
+    }
+    for (r, row) in tiles.iter_mut().enumerate() {
+        let mut inside = false;
+        for (c, tile) in row.iter_mut().enumerate() {
+            if *tile == Tile::Loop {
+                if g[r][c].is_some_and(|p| p.opens(Dir::N)) {
+                    inside = !inside;
+                }
+            } else if inside {
+                *tile = Tile::Inside;
+            }
+        }
+    }
+    tiles
+}
+
+/// Draws the loop in box-drawing characters, marking enclosed tiles with `●`.
+fn render(g: &Grid, tiles: &[Vec<Tile>]) -> String {
+    let mut out = String::new();
+    for (r, row) in tiles.iter().enumerate() {
+        for (c, tile) in row.iter().enumerate() {
+            out.push(match tile {
+                Tile::Loop => g[r][c].map_or('?', Pipe::box_char),
+                Tile::Inside => '●',
+                Tile::Outside => ' ',
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
 fn turn(d: Dir, p: Pipe) -> Option<Dir> {
     if p.0 == d.invert() {
         Some(p.1)
@@ -85,71 +167,184 @@ fn parse_pipe(c: char) -> Option<Pipe> {
     }
 }
 
-fn starts(g: &Grid, p: (usize, usize)) -> Vec<((usize, usize), Dir)> {
-    let mut starts = Vec::new();
+/// Directions from `p` towards neighbouring pipes that open back onto it.
+fn connections(g: &Grid, p: (usize, usize)) -> Vec<Dir> {
+    let mut dirs = Vec::new();
     for d in [Dir::N, Dir::S, Dir::E, Dir::W] {
         if let Some(pp) = dmove(g, d, p) {
-            if let Some(pipe) = get(&g, pp) {
-                if let Some(dd) = turn(d, pipe) {
-                    starts.push((pp, dd));
+            if let Some(pipe) = get(g, pp) {
+                if turn(d, pipe).is_some() {
+                    dirs.push(d);
                 }
             }
         }
     }
-    assert!(starts.len() == 2);
-    starts
+    dirs
+}
+
+/// Follows the pipe at `start` round to itself, returning the steps taken (starting with
+/// `start`), or `None` if the pipes don't close into a loop.
+fn trace(g: &Grid, start: (usize, usize)) -> Option<Vec<Step>> {
+    let start_pipe = get(g, start)?;
+    let mut path = Vec::new();
+    let mut cur = start;
+    let mut d = start_pipe.0;
+    loop {
+        path.push(Step { pos: cur, dir: d });
+        cur = dmove(g, d, cur)?;
+        if cur == start {
+            return turn(d, start_pipe).map(|_| path);
+        }
+        d = turn(d, get(g, cur)?)?;
+    }
+}
+
+/// A copy of the grid with every pipe not on the loop removed.
+fn clean(g: &Grid, path: &[Step]) -> Grid {
+    let mut cleaned: Grid = g.iter().map(|row| vec![None; row.len()]).collect();
+    for step in path {
+        cleaned[step.pos.0][step.pos.1] = get(g, step.pos);
+    }
+    cleaned
+}
+
+/// The greatest distance from the start along the loop, and the tiles at that distance.
+fn farthest(path: &[Step]) -> (usize, Vec<(usize, usize)>) {
+    let n = path.len();
+    let dist = n / 2;
+    let tiles = path
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i.min(n - i) == dist)
+        .map(|(_, step)| step.pos)
+        .collect();
+    (dist, tiles)
+}
+
+/// Works out which pipe `S` hides and puts it in the grid. Where more than two neighbours
+/// connect, tries each pair and keeps the first that closes a loop, warning if others do too.
+fn infer_start(g: &mut Grid, start: (usize, usize)) -> Result<Vec<Step>, String> {
+    let dirs = connections(g, start);
+    let mut loops = Vec::new();
+    for (i, &a) in dirs.iter().enumerate() {
+        for &b in dirs[i + 1..].iter() {
+            g[start.0][start.1] = Some(Pipe(a, b));
+            if let Some(path) = trace(g, start) {
+                loops.push((Pipe(a, b), path));
+            }
+        }
+    }
+    if loops.len() > 1 {
+        let shapes: Vec<char> = loops.iter().map(|(p, _)| p.ascii_char()).collect();
+        eprintln!(
+            "S at {:?} could be any of {:?}; using {}",
+            start, shapes, shapes[0]
+        );
+    }
+    match loops.into_iter().next() {
+        Some((pipe, path)) => {
+            g[start.0][start.1] = Some(pipe);
+            Ok(path)
+        }
+        None => {
+            g[start.0][start.1] = None;
+            Err(format!(
+                "no pipe at {:?} closes a loop through its {} connecting neighbours",
+                start,
+                dirs.len()
+            ))
+        }
+    }
 }
 
-fn main() {
-    let file = File::open("day10.txt").unwrap();
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut show_render = false;
+    let mut show_inside = false;
+    let mut show_loop = false;
+    let mut show_clean = false;
+    let mut show_farthest = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--render" => show_render = true,
+            "--inside" => show_inside = true,
+            "--loop" => show_loop = true,
+            "--clean" => show_clean = true,
+            "--farthest" => show_farthest = true,
+            _ => return Err(format!("unknown argument {:?}", arg).into()),
+        }
+    }
+
+    let file = File::open("day10.txt")?;
     let lines = BufReader::new(file).lines();
     let mut grid = Vec::new();
-    let mut start = (0, 0);
+    let mut start = None;
     for (row, line) in lines.enumerate() {
-        let l = line.unwrap();
-        let pipes: Vec<Option<Pipe>> = l
-            .chars()
-            .enumerate()
-            .map(|(col, c)| {
-                if c == 'S' {
-                    start = (row, col);
-                    None
-                } else {
-                    parse_pipe(c)
+        let mut pipes = Vec::new();
+        for (col, c) in line?.chars().enumerate() {
+            if c == 'S' {
+                if let Some(first) = start {
+                    return Err(format!("S found at both {:?} and {:?}", first, (row, col)).into());
                 }
-            })
-            .collect();
+                start = Some((row, col));
+                pipes.push(None);
+            } else {
+                pipes.push(parse_pipe(c));
+            }
+        }
         grid.push(pipes);
     }
-    let mut cursors = starts(&grid, start);
-    let mut dist = 1;
-    while cursors[0].0 != cursors[1].0 {
-        cursors = cursors
-            .iter()
-            .map(|&(p, d)| {
-                let pp = dmove(&grid, d, p).unwrap();
-                let pipe = get(&grid, pp).unwrap();
-                let dd = turn(d, pipe).unwrap();
-                (pp, dd)
-            })
-            .collect();
-        dist += 1;
-    }
+    let start = start.ok_or("no S in the grid")?;
+    let path = infer_start(&mut grid, start)?;
+    let (dist, far) = farthest(&path);
     println!("{dist}");
 
-    let mut last = start;
-    let (mut cur, mut d) = starts(&grid, start)[0];
     let mut shoelace: isize = 0;
-    loop {
+    for (i, step) in path.iter().enumerate() {
+        let last = step.pos;
+        let cur = path[(i + 1) % path.len()].pos;
         shoelace += (last.0 as isize) * (cur.1 as isize) - (cur.0 as isize) * (last.1 as isize);
-        last = cur;
-        cur = dmove(&grid, d, cur).unwrap();
-        if cur == start {
-            break;
+    }
+    println!("{}", (shoelace.abs() - path.len() as isize) / 2 + 1);
+
+    if show_farthest {
+        for (r, c) in far {
+            println!("{r} {c} at {dist}");
+        }
+    }
+    if show_loop {
+        for step in &path {
+            println!("{} {} {:?}", step.pos.0, step.pos.1, step.dir);
+        }
+    }
+    let grid = clean(&grid, &path);
+    if show_clean {
+        for (r, row) in grid.iter().enumerate() {
+            let line: String = row
+                .iter()
+                .enumerate()
+                .map(|(c, pipe)| match pipe {
+                    _ if (r, c) == start => 'S',
+                    Some(p) => p.ascii_char(),
+                    None => '.',
+                })
+                .collect();
+            println!("{line}");
+        }
+    }
+    if show_render || show_inside {
+        let tiles = classify(&grid, &path);
+        if show_render {
+            print!("{}", render(&grid, &tiles));
+        }
+        if show_inside {
+            for (r, row) in tiles.iter().enumerate() {
+                for (c, &tile) in row.iter().enumerate() {
+                    if tile == Tile::Inside {
+                        println!("{r} {c}");
+                    }
+                }
+            }
         }
-        let pipe = get(&grid, cur).unwrap();
-        d = turn(d, pipe).unwrap();
     }
-    shoelace += (last.0 as isize) * (cur.1 as isize) - (cur.0 as isize) * (last.1 as isize);
-    println!("{}", (shoelace.abs() - (dist - 1) * 2) / 2);
+    Ok(())
 }
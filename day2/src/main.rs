@@ -2,13 +2,14 @@ use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::u32;
 use nom::combinator::value;
+use nom::error::{Error as ParseError, ErrorKind};
 use nom::multi::separated_list1;
 use nom::sequence::tuple;
 use nom::IResult;
 use std::cmp;
+use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::ops::Add;
 
 #[derive(Clone, Copy, Debug)]
 struct Counts {
@@ -21,17 +22,19 @@ impl Counts {
     fn is_within(self, other: Self) -> bool {
         self.r <= other.r && self.g <= other.g && self.b <= other.b
     }
-}
 
-impl Add for Counts {
-    type Output = Self;
+    fn power(self) -> Option<u64> {
+        u64::from(self.r)
+            .checked_mul(u64::from(self.g))?
+            .checked_mul(u64::from(self.b))
+    }
 
-    fn add(self, other: Self) -> Self {
-        Counts {
-            r: self.r + other.r,
-            g: self.g + other.g,
-            b: self.b + other.b,
-        }
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Counts {
+            r: self.r.checked_add(other.r)?,
+            g: self.g.checked_add(other.g)?,
+            b: self.b.checked_add(other.b)?,
+        })
     }
 }
 
@@ -62,8 +65,12 @@ fn parse_counted_color(s: &str) -> IResult<&str, Counts> {
 }
 
 fn parse_round(s: &str) -> IResult<&str, Counts> {
-    let (s, counts) = separated_list1(tag(", "), parse_counted_color)(s)?;
-    Ok((s, counts.iter().fold(EMPTY, |acc, &x| acc + x)))
+    let (rest, counts) = separated_list1(tag(", "), parse_counted_color)(s)?;
+    let total = counts
+        .iter()
+        .try_fold(EMPTY, |acc, &x| acc.checked_add(x))
+        .ok_or(nom::Err::Failure(ParseError::new(s, ErrorKind::TooLarge)))?;
+    Ok((rest, total))
 }
 
 fn parse_game(s: &str) -> IResult<&str, Game> {
@@ -76,28 +83,37 @@ fn parse_game(s: &str) -> IResult<&str, Game> {
     Ok((s, Game { id, rounds }))
 }
 
-fn main() {
-    let file = File::open("day2.txt").unwrap();
+fn main() -> Result<(), Box<dyn Error>> {
+    let file = File::open("day2.txt")?;
     let lines = BufReader::new(file).lines().map(|l| l.unwrap());
     const LIMITS: Counts = Counts {
         r: 12,
         g: 13,
         b: 14,
     };
-    let mut good_ids = 0;
-    let mut power_sum = 0;
+    let mut good_ids: u64 = 0;
+    let mut power_sum: u64 = 0;
     for line in lines {
-        let (remaining, game) = parse_game(&line).unwrap();
-        assert!(remaining.is_empty());
+        let (remaining, game) = parse_game(&line).map_err(|e| e.to_owned())?;
+        if !remaining.is_empty() {
+            return Err(format!("trailing input {:?}", remaining).into());
+        }
         let is_good = game.rounds.iter().all(|x| x.is_within(LIMITS));
         if is_good {
-            good_ids += game.id;
+            good_ids = good_ids
+                .checked_add(u64::from(game.id))
+                .ok_or("sum of good game ids overflowed")?;
         }
 
         let min_needed = game.rounds.iter().fold(EMPTY, |acc, &x| max_each(acc, x));
-        let power = min_needed.r * min_needed.g * min_needed.b;
-        power_sum += power;
+        let power = min_needed
+            .power()
+            .ok_or_else(|| format!("power of game {} overflowed", game.id))?;
+        power_sum = power_sum
+            .checked_add(power)
+            .ok_or("sum of powers overflowed")?;
     }
     println!("{}", good_ids);
     println!("{}", power_sum);
+    Ok(())
 }
@@ -1,9 +1,13 @@
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::error::Error;
-use std::fs;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::mem;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Tile {
     Digit(u32),
     Symbol(char),
@@ -12,7 +16,7 @@ enum Tile {
 
 type Grid = Vec<Vec<Tile>>;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 struct Loc {
     row: usize,
     col: usize,
@@ -39,39 +43,46 @@ struct Number {
     len: usize,
 }
 
-fn find_numbers(g: &Grid) -> Vec<Number> {
+fn find_numbers_in_row(g: &Grid, row: usize) -> Result<Vec<Number>, String> {
     let mut nums = Vec::new();
-    for row in 0..g.len() {
-        let mut col = 0;
-        while col < g[row].len() {
-            let loc = Loc { row, col };
-            col += 1;
-            if let Tile::Digit(d) = tile_at(g, loc) {
-                let mut value = d;
-                while let Tile::Digit(d) = tile_at(g, Loc { row, col }) {
-                    col += 1;
-                    value = value * 10 + d;
-                    if col == g[row].len() {
-                        break;
-                    }
-                }
-                nums.push(Number {
-                    value,
-                    loc,
-                    len: col - loc.col,
-                });
+    let mut col = 0;
+    while col < g[row].len() {
+        let loc = Loc { row, col };
+        col += 1;
+        if let Tile::Digit(d) = tile_at(g, loc) {
+            let mut value = d;
+            while col < g[row].len() {
+                let Tile::Digit(d) = tile_at(g, Loc { row, col }) else {
+                    break;
+                };
+                col += 1;
+                value = value
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add(d))
+                    .ok_or_else(|| format!("number at column {} is too large", loc.col))?;
             }
+            nums.push(Number {
+                value,
+                loc,
+                len: col - loc.col,
+            });
         }
     }
-    nums
+    Ok(nums)
+}
+
+fn find_numbers(g: &Grid) -> Result<Vec<Number>, String> {
+    let mut nums = Vec::new();
+    for row in 0..g.len() {
+        nums.extend(find_numbers_in_row(g, row).map_err(|e| format!("row {}: {}", row, e))?);
+    }
+    Ok(nums)
 }
 
 fn near_symbols(g: &Grid, n: Number) -> Vec<(char, Loc)> {
     let mut symbols = Vec::new();
-    for row in n.loc.row.checked_sub(1).unwrap_or(0)..cmp::min(g.len(), n.loc.row + 2) {
-        for col in
-            n.loc.col.checked_sub(1).unwrap_or(0)..cmp::min(g[row].len(), n.loc.col + n.len + 1)
-        {
+    for row in n.loc.row.saturating_sub(1)..cmp::min(g.len(), n.loc.row + 2) {
+        for col in n.loc.col.saturating_sub(1)..cmp::min(g[row].len(), n.loc.col + n.len + 1) {
             let loc = Loc { row, col };
             if let Tile::Symbol(s) = tile_at(g, loc) {
                 symbols.push((s, loc));
@@ -81,35 +92,426 @@ fn near_symbols(g: &Grid, n: Number) -> Vec<(char, Loc)> {
     symbols
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Rule {
+    /// Not a symbol at all; adjacent numbers are not parts because of it.
+    Ignore,
+    /// Marks adjacent numbers as parts but contributes nothing else.
+    Part,
+    /// Product of the adjacent numbers if there are exactly this many.
+    Gear(usize),
+    /// Product of all adjacent numbers.
+    Multiply,
+    /// Sum of all adjacent numbers.
+    Sum,
+}
+
+impl Rule {
+    /// Value this symbol contributes given its adjacent numbers, or `None` on overflow.
+    fn evaluate(self, values: &[u32]) -> Option<u64> {
+        match self {
+            Rule::Ignore | Rule::Part => Some(0),
+            Rule::Gear(arity) if values.len() != arity => Some(0),
+            Rule::Gear(_) | Rule::Multiply => values
+                .iter()
+                .try_fold(1u64, |acc, &v| acc.checked_mul(u64::from(v))),
+            Rule::Sum => values
+                .iter()
+                .try_fold(0u64, |acc, &v| acc.checked_add(u64::from(v))),
+        }
+    }
+}
+
+struct Rules(HashMap<char, Rule>);
+
+impl Rules {
+    fn get(&self, symbol: char) -> Rule {
+        self.0.get(&symbol).copied().unwrap_or(Rule::Part)
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules(HashMap::from([('*', Rule::Gear(2))]))
+    }
+}
+
+/// Parses lines of the form `<symbol> <rule> [arity]` on top of the default rules,
+/// e.g. `* gear 2`, `# sum`, `% multiply`, `$ part` or `@ ignore`.
+fn parse_rules(s: &str) -> Result<Rules, Box<dyn Error>> {
+    let mut rules = Rules::default();
+    for (i, line) in s.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let mut symbol = words[0].chars();
+        let symbol = match (symbol.next(), symbol.next()) {
+            (Some(c), None) if parse_tile(c) == Tile::Symbol(c) => c,
+            _ => return Err(format!("line {}: bad symbol {:?}", i + 1, words[0]).into()),
+        };
+        let rule = match words[1..] {
+            ["ignore"] => Rule::Ignore,
+            ["part"] => Rule::Part,
+            ["gear", arity] => Rule::Gear(arity.parse()?),
+            ["multiply"] => Rule::Multiply,
+            ["sum"] => Rule::Sum,
+            _ => return Err(format!("line {}: bad rule {:?}", i + 1, line).into()),
+        };
+        rules.0.insert(symbol, rule);
+    }
+    Ok(rules)
+}
+
+struct Graph {
+    /// Every number with the locations of the symbols it touches.
+    numbers: Vec<(Number, Vec<Loc>)>,
+    symbols: BTreeMap<Loc, char>,
+}
+
+fn build_graph(g: &Grid, rules: &Rules) -> Result<Graph, String> {
+    let mut numbers = Vec::new();
+    let mut symbols = BTreeMap::new();
+    for n in find_numbers(g)? {
+        let mut locs = Vec::new();
+        for (s, loc) in near_symbols(g, n) {
+            if rules.get(s) != Rule::Ignore {
+                symbols.insert(loc, s);
+                locs.push(loc);
+            }
+        }
+        numbers.push((n, locs));
+    }
+    Ok(Graph { numbers, symbols })
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '"' | '\\' => format!("\\{}", c),
+        c if c.is_control() => format!("\\u{:04x}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+impl Graph {
+    fn symbol_ids(&self) -> HashMap<Loc, usize> {
+        self.symbols
+            .keys()
+            .enumerate()
+            .map(|(id, &loc)| (loc, id))
+            .collect()
+    }
+
+    fn orphans(&self) -> impl Iterator<Item = Number> + '_ {
+        self.numbers
+            .iter()
+            .filter(|(_, locs)| locs.is_empty())
+            .map(|&(n, _)| n)
+    }
+
+    fn to_json(&self) -> String {
+        let ids = &self.symbol_ids();
+        let numbers: Vec<String> = self
+            .numbers
+            .iter()
+            .enumerate()
+            .map(|(id, (n, _))| {
+                format!(
+                    "{{\"id\":{},\"value\":{},\"row\":{},\"col\":{},\"len\":{}}}",
+                    id, n.value, n.loc.row, n.loc.col, n.len
+                )
+            })
+            .collect();
+        let symbols: Vec<String> = self
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(id, (loc, &s))| {
+                format!(
+                    "{{\"id\":{},\"symbol\":\"{}\",\"row\":{},\"col\":{}}}",
+                    id,
+                    escape(s),
+                    loc.row,
+                    loc.col
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .numbers
+            .iter()
+            .enumerate()
+            .flat_map(|(id, (_, locs))| {
+                locs.iter()
+                    .map(move |loc| format!("{{\"number\":{},\"symbol\":{}}}", id, ids[loc]))
+            })
+            .collect();
+        let orphans: Vec<String> = self
+            .numbers
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, locs))| locs.is_empty())
+            .map(|(id, _)| id.to_string())
+            .collect();
+        format!(
+            "{{\"numbers\":[{}],\"symbols\":[{}],\"edges\":[{}],\"orphans\":[{}]}}\n",
+            numbers.join(","),
+            symbols.join(","),
+            edges.join(","),
+            orphans.join(",")
+        )
+    }
+
+    fn to_dot(&self) -> String {
+        let ids = self.symbol_ids();
+        let mut dot = String::from("graph schematic {\n");
+        for (id, (n, locs)) in self.numbers.iter().enumerate() {
+            let style = if locs.is_empty() {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            writeln!(
+                dot,
+                "    n{} [label=\"{} @{},{}+{}\"{}];",
+                id, n.value, n.loc.row, n.loc.col, n.len, style
+            )
+            .unwrap();
+        }
+        for (id, (loc, &s)) in self.symbols.iter().enumerate() {
+            writeln!(
+                dot,
+                "    s{} [label=\"{} @{},{}\", shape=box];",
+                id,
+                escape(s),
+                loc.row,
+                loc.col
+            )
+            .unwrap();
+        }
+        for (id, (_, locs)) in self.numbers.iter().enumerate() {
+            for loc in locs {
+                writeln!(dot, "    n{} -- s{};", id, ids[loc]).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    part_numbers: u64,
+    symbol_values: u64,
+}
+
+impl Totals {
+    fn add_part(&mut self, value: u32) -> Result<(), Box<dyn Error>> {
+        self.part_numbers = self
+            .part_numbers
+            .checked_add(u64::from(value))
+            .ok_or("sum of part numbers overflowed")?;
+        Ok(())
+    }
+
+    fn add_symbol(&mut self, rules: &Rules, s: char, values: &[u32]) -> Result<(), Box<dyn Error>> {
+        let value = rules
+            .get(s)
+            .evaluate(values)
+            .ok_or_else(|| format!("value of symbol {:?} overflowed", s))?;
+        self.symbol_values = self
+            .symbol_values
+            .checked_add(value)
+            .ok_or("sum of symbol values overflowed")?;
+        Ok(())
+    }
+}
+
+fn graph_totals(graph: &Graph, rules: &Rules) -> Result<Totals, Box<dyn Error>> {
+    let mut totals = Totals::default();
+    let mut adjacent: HashMap<Loc, Vec<u32>> = HashMap::new();
+    for (n, locs) in graph.numbers.iter() {
+        if !locs.is_empty() {
+            totals.add_part(n.value)?;
+        }
+        for &loc in locs.iter() {
+            adjacent.entry(loc).or_default().push(n.value);
+        }
+    }
+    for (loc, values) in adjacent {
+        totals.add_symbol(rules, graph.symbols[&loc], &values)?;
+    }
+    Ok(totals)
+}
+
+struct Stream<'a> {
+    rules: &'a Rules,
+    totals: Totals,
+    /// Symbols by absolute location, with the numbers seen next to them so far.
+    pending: BTreeMap<Loc, (char, Vec<u32>)>,
+}
+
+impl Stream<'_> {
+    /// Scans the numbers on `row` of a window whose first row is row `first` of the schematic.
+    fn scan_row(&mut self, window: &Grid, first: usize, row: usize) -> Result<(), Box<dyn Error>> {
+        let nums =
+            find_numbers_in_row(window, row).map_err(|e| format!("row {}: {}", first + row, e))?;
+        for n in nums {
+            let mut is_part = false;
+            for (s, loc) in near_symbols(window, n) {
+                if self.rules.get(s) != Rule::Ignore {
+                    is_part = true;
+                    let loc = Loc {
+                        row: first + loc.row,
+                        col: loc.col,
+                    };
+                    self.pending
+                        .entry(loc)
+                        .or_insert((s, Vec::new()))
+                        .1
+                        .push(n.value);
+                }
+            }
+            if is_part {
+                self.totals.add_part(n.value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the symbols on rows before `row`, which can gain no more numbers.
+    fn flush_before(&mut self, row: usize) -> Result<(), Box<dyn Error>> {
+        let rest = self.pending.split_off(&Loc { row, col: 0 });
+        for (_, (s, values)) in mem::replace(&mut self.pending, rest) {
+            self.totals.add_symbol(self.rules, s, &values)?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the same totals as `graph_totals` while holding at most three rows in memory.
+fn stream_totals(reader: impl BufRead, rules: &Rules) -> Result<Totals, Box<dyn Error>> {
+    let mut stream = Stream {
+        rules,
+        totals: Totals::default(),
+        pending: BTreeMap::new(),
+    };
+    // Rows `first..first + window.len()` of the schematic.
+    let mut window: Grid = Vec::new();
+    let mut first = 0;
+    for line in reader.lines() {
+        window.push(line?.chars().map(parse_tile).collect());
+        if first == 0 && window.len() == 2 {
+            stream.scan_row(&window, first, 0)?;
+        } else if window.len() == 3 {
+            stream.scan_row(&window, first, 1)?;
+            stream.flush_before(first + 1)?;
+            window.remove(0);
+            first += 1;
+        }
+    }
+    if !window.is_empty() {
+        stream.scan_row(&window, first, window.len() - 1)?;
+    }
+    stream.flush_before(usize::MAX)?;
+    Ok(stream.totals)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut rules = Rules::default();
+    let mut json_path = None;
+    let mut dot_path = None;
+    let mut list_orphans = false;
+    let mut stream = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rules" => {
+                let path = args.next().ok_or("--rules needs a file")?;
+                rules = parse_rules(&fs::read_to_string(path)?)?;
+            }
+            "--json" => json_path = Some(args.next().ok_or("--json needs a file")?),
+            "--dot" => dot_path = Some(args.next().ok_or("--dot needs a file")?),
+            "--orphans" => list_orphans = true,
+            "--stream" => stream = true,
+            _ => return Err(format!("unknown argument {:?}", arg).into()),
+        }
+    }
+
+    if stream {
+        if json_path.is_some() || dot_path.is_some() || list_orphans {
+            return Err("--stream cannot export the adjacency graph".into());
+        }
+        let totals = stream_totals(BufReader::new(File::open("day3.txt")?), &rules)?;
+        println!("{}", totals.part_numbers);
+        println!("{}", totals.symbol_values);
+        return Ok(());
+    }
+
     let f = fs::read_to_string("day3.txt")?;
     let g: Grid = f
         .lines()
         .map(|line| line.chars().map(parse_tile).collect())
         .collect();
-    let mut part_numbers = 0;
-    let mut potential_gears = HashMap::new();
-    for &n in find_numbers(&g).iter() {
-        let symbols = near_symbols(&g, n);
-        if !symbols.is_empty() {
-            part_numbers += n.value;
-        }
-        for &(s, loc) in symbols.iter() {
-            if s == '*' {
-                potential_gears
-                    .entry(loc)
-                    .or_insert(Vec::new())
-                    .push(n.value);
-            }
+    let graph = build_graph(&g, &rules)?;
+    let totals = graph_totals(&graph, &rules)?;
+    println!("{}", totals.part_numbers);
+    println!("{}", totals.symbol_values);
+    if list_orphans {
+        for n in graph.orphans() {
+            println!("orphan {} at {},{}", n.value, n.loc.row, n.loc.col);
         }
     }
-    println!("{}", part_numbers);
-    let mut gear_ratios = 0;
-    for (_, values) in potential_gears {
-        if values.len() == 2 {
-            gear_ratios += values[0] * values[1];
-        }
+    if let Some(path) = json_path {
+        fs::write(path, graph.to_json())?;
+    }
+    if let Some(path) = dot_path {
+        fs::write(path, graph.to_dot())?;
     }
-    println!("{}", gear_ratios);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+467..114..
+...*......
+..35..633.
+......#...
+617*......
+.....+.58.
+..592.....
+......755.
+...$.*....
+.664.598.7
+.........*
+";
+
+    fn totals(s: &str) -> (u64, u64, u64, u64) {
+        let rules = Rules::default();
+        let g: Grid = s
+            .lines()
+            .map(|line| line.chars().map(parse_tile).collect())
+            .collect();
+        let graph = graph_totals(&build_graph(&g, &rules).unwrap(), &rules).unwrap();
+        let stream = stream_totals(s.as_bytes(), &rules).unwrap();
+        (
+            graph.part_numbers,
+            graph.symbol_values,
+            stream.part_numbers,
+            stream.symbol_values,
+        )
+    }
+
+    #[test]
+    fn stream_matches_graph() {
+        assert_eq!(totals(EXAMPLE), (4368, 467835, 4368, 467835));
+    }
+
+    #[test]
+    fn digit_in_last_column() {
+        assert_eq!(totals("5\n"), (0, 0, 0, 0));
+        assert_eq!(totals("..5\n.*7\n"), (12, 35, 12, 35));
+    }
+}
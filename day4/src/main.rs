@@ -4,13 +4,16 @@ use nom::multi::separated_list1;
 use nom::sequence::{delimited, tuple};
 use nom::IResult;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 
 #[derive(Debug)]
 struct Card {
     id: u32,
-    winners: HashSet<u32>,
+    winners: Vec<u32>,
     numbers: Vec<u32>,
 }
 
@@ -32,7 +35,7 @@ fn parse_card(s: &str) -> IResult<&str, Card> {
         s,
         Card {
             id,
-            winners: HashSet::from_iter(winners.iter().cloned()),
+            winners,
             numbers,
         },
     ))
@@ -45,35 +48,230 @@ fn num_winners(card: &Card) -> u32 {
         .count() as u32
 }
 
-fn score(card: &Card) -> u32 {
+fn score(card: &Card) -> Option<u64> {
     match num_winners(card) {
-        0 => 0,
-        c => 1 << (c - 1),
+        0 => Some(0),
+        c => 1u64.checked_shl(c - 1),
     }
 }
 
-fn main() {
-    let file = File::open("day4.txt").unwrap();
-    let lines = BufReader::new(file).lines().map(|l| l.unwrap());
-    let mut scores = 0;
-    let mut copies: HashMap<u32, u32> = HashMap::new();
-    let mut total_cards = 0;
-    for line in lines {
-        let (remaining, card) = parse_card(&line).unwrap();
-        assert!(remaining.is_empty());
-        scores += score(&card);
-
-        let count = 1 + copies.get(&card.id).unwrap_or(&0);
-        total_cards += count;
+#[derive(Debug)]
+enum Issue {
+    DuplicateWinner { card: u32, number: u32 },
+    DuplicateNumber { card: u32, number: u32 },
+    MissingIds { from: u32, to: u32 },
+    OutOfOrder { card: u32, previous: u32 },
+    CopiesPastEnd { card: u32, last: u32 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::DuplicateWinner { card, number } => {
+                write!(f, "card {}: winning number {} listed twice", card, number)
+            }
+            Issue::DuplicateNumber { card, number } => {
+                write!(f, "card {}: number {} listed twice", card, number)
+            }
+            Issue::MissingIds { from, to } if from == to => write!(f, "card {} is missing", from),
+            Issue::MissingIds { from, to } => write!(f, "cards {} to {} are missing", from, to),
+            Issue::OutOfOrder { card, previous } => {
+                write!(f, "card {} comes after card {}", card, previous)
+            }
+            Issue::CopiesPastEnd { card, last } => {
+                write!(f, "card {} wins copies past the last card {}", card, last)
+            }
+        }
+    }
+}
+
+fn duplicates(list: &[u32]) -> Vec<u32> {
+    let mut seen = HashSet::new();
+    let mut dups = Vec::new();
+    for &n in list {
+        if !seen.insert(n) && !dups.contains(&n) {
+            dups.push(n);
+        }
+    }
+    dups
+}
+
+fn validate(cards: &[Card]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let last = cards.iter().map(|c| c.id).max().unwrap_or(0);
+    let mut previous: Option<u32> = None;
+    for card in cards {
+        for number in duplicates(&card.winners) {
+            issues.push(Issue::DuplicateWinner {
+                card: card.id,
+                number,
+            });
+        }
+        for number in duplicates(&card.numbers) {
+            issues.push(Issue::DuplicateNumber {
+                card: card.id,
+                number,
+            });
+        }
+        if let Some(previous) = previous.filter(|&p| card.id < p) {
+            issues.push(Issue::OutOfOrder {
+                card: card.id,
+                previous,
+            });
+        }
+        previous = Some(card.id);
+        if card
+            .id
+            .checked_add(num_winners(card))
+            .is_none_or(|end| end > last)
+        {
+            issues.push(Issue::CopiesPastEnd {
+                card: card.id,
+                last,
+            });
+        }
+    }
+
+    let mut ids: Vec<u32> = cards.iter().map(|c| c.id).collect();
+    ids.sort();
+    ids.dedup();
+    let mut seen = 0;
+    for id in ids {
+        if id > seen + 1 {
+            issues.push(Issue::MissingIds {
+                from: seen + 1,
+                to: id - 1,
+            });
+        }
+        seen = id;
+    }
+    issues
+}
+
+#[derive(Debug)]
+struct Cascade {
+    id: u32,
+    winners: u32,
+    copies: u64,
+    /// Earlier cards that won copies of this one, with how many copies each gave.
+    contributors: Vec<(u32, u64)>,
+}
+
+fn to_csv(cascade: &[Cascade]) -> String {
+    let mut csv = String::from("card,winners,copies,contributors\n");
+    for c in cascade {
+        let contributors: Vec<String> = c
+            .contributors
+            .iter()
+            .map(|(id, copies)| format!("{}x{}", id, copies))
+            .collect();
+        csv += &format!(
+            "{},{},{},{}\n",
+            c.id,
+            c.winners,
+            c.copies,
+            contributors.join(" ")
+        );
+    }
+    csv
+}
+
+fn to_json(cascade: &[Cascade]) -> String {
+    let cards: Vec<String> = cascade
+        .iter()
+        .map(|c| {
+            let contributors: Vec<String> = c
+                .contributors
+                .iter()
+                .map(|(id, copies)| format!("{{\"card\":{},\"copies\":{}}}", id, copies))
+                .collect();
+            format!(
+                "{{\"card\":{},\"winners\":{},\"copies\":{},\"contributors\":[{}]}}",
+                c.id,
+                c.winners,
+                c.copies,
+                contributors.join(",")
+            )
+        })
+        .collect();
+    format!("[{}]\n", cards.join(","))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut csv_path = None;
+    let mut json_path = None;
+    let mut strict = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csv" => csv_path = Some(args.next().ok_or("--csv needs a file")?),
+            "--json" => json_path = Some(args.next().ok_or("--json needs a file")?),
+            "--strict" => strict = true,
+            _ => return Err(format!("unknown argument {:?}", arg).into()),
+        }
+    }
+
+    let file = File::open("day4.txt")?;
+    let mut cards = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let (remaining, card) = parse_card(&line).map_err(|e| e.to_owned())?;
+        if !remaining.is_empty() {
+            return Err(format!("trailing input {:?}", remaining).into());
+        }
+        cards.push(card);
+    }
+    let issues = validate(&cards);
+    for issue in issues.iter() {
+        eprintln!("{}", issue);
+    }
+    if strict && !issues.is_empty() {
+        return Err(format!("{} problems with the scratchcards", issues.len()).into());
+    }
+
+    let mut scores: u64 = 0;
+    let mut copies: HashMap<u32, (u64, Vec<(u32, u64)>)> = HashMap::new();
+    let mut cascade = Vec::new();
+    let mut total_cards: u64 = 0;
+    for card in cards {
+        let score = score(&card).ok_or_else(|| format!("score of card {} overflowed", card.id))?;
+        scores = scores
+            .checked_add(score)
+            .ok_or("sum of scores overflowed")?;
+
+        let (won, contributors) = copies.remove(&card.id).unwrap_or_default();
+        let count = won
+            .checked_add(1)
+            .ok_or_else(|| format!("copies of card {} overflowed", card.id))?;
+        total_cards = total_cards
+            .checked_add(count)
+            .ok_or("total number of cards overflowed")?;
         let winners = num_winners(&card);
-        for id in card.id + 1..=card.id + winners {
-            copies
-                .entry(id)
-                .and_modify(|e| *e += count)
-                .or_insert(count);
+        let last_won = card
+            .id
+            .checked_add(winners)
+            .ok_or_else(|| format!("card {} wins copies of cards past {}", card.id, u32::MAX))?;
+        for id in (card.id..last_won).map(|id| id + 1) {
+            let e = copies.entry(id).or_default();
+            e.0 =
+                e.0.checked_add(count)
+                    .ok_or_else(|| format!("copies of card {} overflowed", id))?;
+            e.1.push((card.id, count));
         }
-        copies.remove(&card.id);
+        cascade.push(Cascade {
+            id: card.id,
+            winners,
+            copies: count,
+            contributors,
+        });
     }
     println!("{}", scores);
     println!("{}", total_cards);
+    if let Some(path) = csv_path {
+        fs::write(path, to_csv(&cascade))?;
+    }
+    if let Some(path) = json_path {
+        fs::write(path, to_json(&cascade))?;
+    }
+    Ok(())
 }
@@ -4,8 +4,12 @@ use nom::multi::{many1, separated_list1};
 use nom::sequence::{separated_pair, tuple};
 use nom::IResult;
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::ops::Range;
 
 struct MapLine {
     dst_start: u64,
@@ -19,31 +23,354 @@ struct Map<'a> {
     lines: Vec<MapLine>,
 }
 
-impl Map<'_> {
-    fn get(&self, src: u64) -> u64 {
+impl<'a> Map<'a> {
+    /// Looks up `src` by binary search; only valid for maps built by `then`, whose lines are
+    /// sorted by source and disjoint.
+    fn lookup(&self, src: u64) -> u64 {
+        let i = self
+            .lines
+            .partition_point(|line| line.src_start + line.len <= src);
+        match self.lines.get(i) {
+            Some(line) if line.src_start <= src => src - line.src_start + line.dst_start,
+            _ => src,
+        }
+    }
+
+    /// Splits `src` into the pieces mapped by each line, paired with where each piece starts
+    /// in the destination.
+    fn split(&self, src: Range<u64>) -> Vec<(Range<u64>, u64)> {
+        let mut mapped = Vec::new();
+        let mut unmapped = vec![src];
         for line in self.lines.iter() {
-            if line.src_start <= src && src < line.src_start + line.len {
-                return src - line.src_start + line.dst_start;
+            let line_end = line.src_start + line.len;
+            let mut rest = Vec::new();
+            for r in unmapped {
+                let start = cmp::max(r.start, line.src_start);
+                let end = cmp::min(r.end, line_end);
+                if start < end {
+                    mapped.push((start..end, start - line.src_start + line.dst_start));
+                    if r.start < start {
+                        rest.push(r.start..start);
+                    }
+                    if end < r.end {
+                        rest.push(end..r.end);
+                    }
+                } else {
+                    rest.push(r);
+                }
+            }
+            unmapped = rest;
+        }
+        mapped.extend(
+            unmapped
+                .into_iter()
+                .filter(|r| !r.is_empty())
+                .map(|r| (r.clone(), r.start)),
+        );
+        mapped
+    }
+
+    fn get_range(&self, src: Range<u64>) -> Vec<Range<u64>> {
+        self.split(src)
+            .into_iter()
+            .map(|(r, dst)| dst..dst + (r.end - r.start))
+            .collect()
+    }
+
+    /// Every source range that maps into `dst`; there may be none, or several overlapping lines.
+    fn preimage(&self, dst: Range<u64>) -> Vec<Range<u64>> {
+        let mut srcs = Vec::new();
+        for (r, start) in self.split(0..u64::MAX) {
+            let lo = cmp::max(dst.start, start);
+            let hi = cmp::min(dst.end, start + (r.end - r.start));
+            if lo < hi {
+                srcs.push(r.start + (lo - start)..r.start + (hi - start));
             }
         }
-        return src;
+        srcs
+    }
+
+    /// Composes this map with `next`, giving one map with sorted, disjoint lines.
+    fn then(&self, next: &Map<'a>) -> Map<'a> {
+        let mut lines: Vec<MapLine> = Vec::new();
+        for (r, dst) in self.split(0..u64::MAX) {
+            for (next_r, next_dst) in next.split(dst..dst + (r.end - r.start)) {
+                let src_start = r.start + (next_r.start - dst);
+                let len = next_r.end - next_r.start;
+                if src_start != next_dst {
+                    lines.push(MapLine {
+                        dst_start: next_dst,
+                        src_start,
+                        len,
+                    });
+                }
+            }
+        }
+        lines.sort_by_key(|line| line.src_start);
+        let mut merged: Vec<MapLine> = Vec::new();
+        for line in lines {
+            match merged.last_mut() {
+                Some(prev)
+                    if prev.src_start + prev.len == line.src_start
+                        && prev.dst_start + prev.len == line.dst_start =>
+                {
+                    prev.len += line.len
+                }
+                _ => merged.push(line),
+            }
+        }
+        Map {
+            src: self.src,
+            dst: next.dst,
+            lines: merged,
+        }
+    }
+}
+
+impl fmt::Display for Map<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}-to-{} map:", self.src, self.dst)?;
+        for line in self.lines.iter() {
+            writeln!(f, "{} {} {}", line.dst_start, line.src_start, line.len)?;
+        }
+        Ok(())
     }
 }
 
 struct Almanac<'a> {
     maps: HashMap<&'a str, Map<'a>>,
+    /// Maps whose source category already had a map, kept so validation can see them.
+    branches: Vec<Map<'a>>,
+}
+
+#[derive(Debug)]
+enum Problem<'a> {
+    MissingLink {
+        category: &'a str,
+    },
+    Cycle {
+        category: &'a str,
+    },
+    Branch {
+        category: &'a str,
+        first: &'a str,
+        second: &'a str,
+    },
+    Overlap {
+        src: &'a str,
+        dst: &'a str,
+        first: usize,
+        second: usize,
+    },
+    EmptyLine {
+        src: &'a str,
+        dst: &'a str,
+        line: usize,
+    },
 }
 
-impl Almanac<'_> {
-    fn seed_to_location(&self, seed_id: u64) -> u64 {
-        let mut category = "seed";
-        let mut id = seed_id;
-        while category != "location" {
-            let m = self.maps.get(category).unwrap();
+impl fmt::Display for Problem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::MissingLink { category } => write!(f, "no map from {}", category),
+            Problem::Cycle { category } => write!(f, "maps from {} loop back to it", category),
+            Problem::Branch {
+                category,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} maps to both {} and {}; only the first is used",
+                category, first, second
+            ),
+            Problem::Overlap {
+                src,
+                dst,
+                first,
+                second,
+            } => write!(
+                f,
+                "{}-to-{} map: lines {} and {} have overlapping sources",
+                src, dst, first, second
+            ),
+            Problem::EmptyLine { src, dst, line } => {
+                write!(f, "{}-to-{} map: line {} is empty", src, dst, line)
+            }
+        }
+    }
+}
+
+impl<'a> Almanac<'a> {
+    /// Checks that each category has one map leading on to `location` and that each map's
+    /// lines are non-empty and have disjoint sources.
+    fn validate(&self) -> Vec<Problem<'a>> {
+        let mut problems = Vec::new();
+        for m in self.branches.iter() {
+            problems.push(Problem::Branch {
+                category: m.src,
+                first: self.maps[m.src].dst,
+                second: m.dst,
+            });
+        }
+        let mut categories: Vec<&'a str> = self
+            .maps
+            .values()
+            .chain(self.branches.iter())
+            .flat_map(|m| [m.src, m.dst])
+            .chain(["seed"])
+            .filter(|&c| c != "location")
+            .collect();
+        categories.sort();
+        categories.dedup();
+        for category in categories {
+            if !self.maps.contains_key(category) {
+                problems.push(Problem::MissingLink { category });
+            } else if self
+                .maps
+                .values()
+                .chain(self.branches.iter())
+                .any(|m| m.src == category && self.route(m.dst, category).is_ok())
+            {
+                problems.push(Problem::Cycle { category });
+            }
+        }
+
+        let mut maps: Vec<&Map> = self.maps.values().chain(self.branches.iter()).collect();
+        maps.sort_by_key(|m| (m.src, m.dst));
+        for m in maps {
+            for (i, line) in m.lines.iter().enumerate() {
+                if line.len == 0 {
+                    problems.push(Problem::EmptyLine {
+                        src: m.src,
+                        dst: m.dst,
+                        line: i,
+                    });
+                }
+                for (j, other) in m.lines.iter().enumerate().skip(i + 1) {
+                    if line.len > 0
+                        && other.len > 0
+                        && line.src_start < other.src_start + other.len
+                        && other.src_start < line.src_start + line.len
+                    {
+                        problems.push(Problem::Overlap {
+                            src: m.src,
+                            dst: m.dst,
+                            first: i,
+                            second: j,
+                        });
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Finds the chain of maps leading from `src` to `dst`.
+    fn route(&self, src: &str, dst: &str) -> Result<Vec<&Map<'a>>, String> {
+        let mut path = Vec::new();
+        let mut seen = HashSet::new();
+        let mut category = src;
+        while category != dst {
+            if !seen.insert(category) {
+                return Err(format!("maps from {} loop without reaching {}", src, dst));
+            }
+            let m = self
+                .maps
+                .get(category)
+                .ok_or_else(|| format!("no map from {} on the way to {}", category, dst))?;
+            path.push(m);
             category = m.dst;
-            id = m.get(id);
         }
-        id
+        Ok(path)
+    }
+
+    fn convert_range(
+        &self,
+        src: &str,
+        dst: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<Range<u64>>, String> {
+        let mut ranges = vec![range];
+        for m in self.route(src, dst)? {
+            ranges = ranges.into_iter().flat_map(|r| m.get_range(r)).collect();
+        }
+        Ok(ranges)
+    }
+
+    /// Every range of `src` that converts into `range` of `dst`.
+    fn preimage_range(
+        &self,
+        src: &str,
+        dst: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<Range<u64>>, String> {
+        let mut ranges = vec![range];
+        for m in self.route(src, dst)?.into_iter().rev() {
+            ranges = ranges.into_iter().flat_map(|r| m.preimage(r)).collect();
+        }
+        Ok(ranges)
+    }
+
+    /// Location ranges that no seed maps to.
+    fn unreachable_locations(&self) -> Result<Vec<Range<u64>>, String> {
+        let mut reachable: Vec<Range<u64>> =
+            self.compose("seed", "location")?.get_range(0..u64::MAX);
+        reachable.sort_by_key(|r| r.start);
+        let mut unreachable = Vec::new();
+        let mut next = 0;
+        for r in reachable {
+            if next < r.start {
+                unreachable.push(next..r.start);
+            }
+            next = cmp::max(next, r.end);
+        }
+        if next < u64::MAX {
+            unreachable.push(next..u64::MAX);
+        }
+        Ok(unreachable)
+    }
+
+    /// Finds the lowest location reachable from `seeds` by scanning location ranges upward and
+    /// mapping each back to seeds, stopping at the first range with a hit.
+    fn lowest_location_by_inverse(&self, seeds: &[Range<u64>]) -> Result<Option<u64>, String> {
+        let forward = self.compose("seed", "location")?;
+        let mut bounds: Vec<u64> = forward
+            .get_range(0..u64::MAX)
+            .into_iter()
+            .flat_map(|r| [r.start, r.end])
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+        for block in bounds.windows(2) {
+            let lowest = self
+                .preimage_range("seed", "location", block[0]..block[1])?
+                .into_iter()
+                .flat_map(|r| {
+                    seeds
+                        .iter()
+                        .map(move |s| cmp::max(r.start, s.start)..cmp::min(r.end, s.end))
+                })
+                .filter(|r| !r.is_empty())
+                .map(|r| forward.lookup(r.start))
+                .min();
+            if lowest.is_some() {
+                return Ok(lowest);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Composes the maps from `src` through to `dst` into a single map.
+    fn compose(&self, src: &'a str, dst: &'a str) -> Result<Map<'a>, String> {
+        let mut composed = Map {
+            src,
+            dst: src,
+            lines: Vec::new(),
+        };
+        for m in self.route(src, dst)? {
+            composed = composed.then(m);
+        }
+        Ok(composed)
     }
 }
 
@@ -60,7 +387,7 @@ fn parse_map_line(s: &str) -> IResult<&str, MapLine> {
     ))
 }
 
-fn parse_map(s: &str) -> IResult<&str, Map> {
+fn parse_map(s: &str) -> IResult<&str, Map<'_>> {
     let (s, ((src, dst), _, _, lines)) = tuple((
         separated_pair(alpha1, tag("-to-"), alpha1),
         tag(" map:"),
@@ -70,7 +397,7 @@ fn parse_map(s: &str) -> IResult<&str, Map> {
     Ok((s, Map { src, dst, lines }))
 }
 
-fn parse_input(s: &str) -> IResult<&str, (Vec<u64>, Almanac)> {
+fn parse_input(s: &str) -> IResult<&str, (Vec<u64>, Almanac<'_>)> {
     let (s, (_, _, seeds, _, maps)) = tuple((
         tag("seeds:"),
         space1,
@@ -79,30 +406,99 @@ fn parse_input(s: &str) -> IResult<&str, (Vec<u64>, Almanac)> {
         separated_list1(multispace1, parse_map),
     ))(s)?;
     let mut src_maps: HashMap<&str, Map> = HashMap::new();
+    let mut branches = Vec::new();
     for map in maps {
-        src_maps.insert(map.src, map);
+        if src_maps.contains_key(map.src) {
+            branches.push(map);
+        } else {
+            src_maps.insert(map.src, map);
+        }
     }
-    let almanac = Almanac { maps: src_maps };
+    let almanac = Almanac {
+        maps: src_maps,
+        branches,
+    };
     Ok((s, (seeds, almanac)))
 }
 
-fn main() {
-    let f = fs::read_to_string("day5.txt").unwrap();
-    let (remaining, (seeds, almanac)) = parse_input(&f).unwrap();
-    assert!(remaining.is_empty());
+fn main() -> Result<(), Box<dyn Error>> {
+    let f = fs::read_to_string("day5.txt")?;
+    let (remaining, (seeds, almanac)) = parse_input(&f).map_err(|e| e.to_owned())?;
+    if !remaining.is_empty() {
+        return Err(format!("trailing input {:?}", remaining).into());
+    }
+    let problems = almanac.validate();
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+
+    let mut inverse = false;
+    let args: Vec<String> = env::args().skip(1).collect();
+    match &args[..] {
+        [] => {}
+        [flag] if flag == "--validate" => {
+            return match problems.len() {
+                0 => Ok(()),
+                n => Err(format!("{} problems with the almanac", n).into()),
+            };
+        }
+        [flag, src, dst] if flag == "--compose" => {
+            print!("{}", almanac.compose(src, dst)?);
+            return Ok(());
+        }
+        [flag, src, dst, value] if flag == "--convert" => {
+            let value: u64 = value.parse()?;
+            println!("{}", almanac.compose(src, dst)?.lookup(value));
+            return Ok(());
+        }
+        [flag, location] if flag == "--seeds-for" => {
+            let location: u64 = location.parse()?;
+            for r in almanac.preimage_range("seed", "location", location..location + 1)? {
+                println!("{}", r.start);
+            }
+            return Ok(());
+        }
+        [flag] if flag == "--unreachable" => {
+            for r in almanac.unreachable_locations()? {
+                println!("{:?}", r);
+            }
+            return Ok(());
+        }
+        [flag] if flag == "--inverse" => inverse = true,
+        _ => {
+            return Err(
+                "usage: day5 [--validate | --compose SRC DST | --convert SRC DST VALUE | \
+                        --seeds-for LOCATION | --unreachable | --inverse]"
+                    .into(),
+            )
+        }
+    }
+
+    let seed_to_location = almanac.compose("seed", "location")?;
     let lowest = seeds
         .iter()
-        .map(|&n| almanac.seed_to_location(n))
+        .map(|&n| seed_to_location.lookup(n))
         .min()
-        .unwrap();
+        .ok_or("no seeds")?;
     println!("{lowest}");
 
-    let mut lowest_chunked = u64::MAX;
-    for chunk in seeds.chunks(2) {
-        let &[start, len] = chunk else { panic!() };
-        for i in start..(start + len) {
-            lowest_chunked = cmp::min(lowest_chunked, almanac.seed_to_location(i));
+    let seed_ranges: Vec<Range<u64>> = seeds
+        .chunks(2)
+        .map(|chunk| {
+            let &[start, len] = chunk else { panic!() };
+            start..(start + len)
+        })
+        .collect();
+    let lowest_chunked = if inverse {
+        almanac.lowest_location_by_inverse(&seed_ranges)?
+    } else {
+        let mut locations = Vec::new();
+        for r in seed_ranges {
+            locations.extend(almanac.convert_range("seed", "location", r)?);
         }
+        locations.iter().map(|r| r.start).min()
     }
+    .ok_or("no seed ranges")?;
     println!("{lowest_chunked}");
+    Ok(())
 }
@@ -1,24 +1,155 @@
+use num::{BigUint, One, Zero};
+use std::cmp;
+use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::iter;
 
-fn parse_int_list(line: &str) -> Vec<u64> {
+fn parse_int_list(line: &str) -> Vec<BigUint> {
     let mut tokens = line.split_whitespace();
     tokens.next();
     tokens.map(|s| s.parse().unwrap()).collect()
 }
 
-fn parse_wide_int(line: &str) -> u64 {
+fn parse_wide_int(line: &str) -> BigUint {
     let mut tokens = line.split_whitespace();
     tokens.next();
     tokens.collect::<Vec<_>>().join("").parse().unwrap()
 }
 
-fn race_distance(button_time: u64, total_time: u64) -> u64 {
+fn race_distance(button_time: &BigUint, total_time: &BigUint) -> BigUint {
     button_time * (total_time - button_time)
 }
 
-fn part1() {
+#[derive(Debug, Eq, PartialEq)]
+struct RaceModel {
+    /// Speed gained per millisecond the button is held.
+    accel: BigUint,
+    /// Speed the boat cannot exceed however long the button is held.
+    max_speed: Option<BigUint>,
+    /// Milliseconds lost after releasing the button before the boat moves.
+    penalty: BigUint,
+}
+
+impl Default for RaceModel {
+    fn default() -> Self {
+        RaceModel {
+            accel: BigUint::one(),
+            max_speed: None,
+            penalty: BigUint::zero(),
+        }
+    }
+}
+
+impl RaceModel {
+    fn distance(&self, button_time: &BigUint, total_time: &BigUint) -> BigUint {
+        let mut speed = &self.accel * button_time;
+        if let Some(max_speed) = &self.max_speed {
+            speed = cmp::min(speed, max_speed.clone());
+        }
+        let start = button_time + &self.penalty;
+        if start >= *total_time {
+            return BigUint::zero();
+        }
+        speed * (total_time - start)
+    }
+
+    /// The button time giving the furthest distance. Distance rises up to it and falls after
+    /// it, so it is either side of the unconstrained peak or of where the speed caps out.
+    fn best_button_time(&self, time: &BigUint) -> BigUint {
+        let moving = if *time > self.penalty {
+            time - &self.penalty
+        } else {
+            BigUint::zero()
+        };
+        let mut candidates = vec![&moving / 2u32, &moving / 2u32 + 1u32];
+        if let (Some(max_speed), false) = (&self.max_speed, self.accel.is_zero()) {
+            let capped = max_speed / &self.accel;
+            candidates.push(&capped + 1u32);
+            candidates.push(capped);
+        }
+        candidates
+            .into_iter()
+            .map(|b| cmp::min(b, time.clone()))
+            .max_by(|a, b| {
+                self.distance(a, time)
+                    .cmp(&self.distance(b, time))
+                    .then(b.cmp(a))
+            })
+            .unwrap()
+    }
+
+    /// The first and last winning button times; every time between them also wins.
+    fn winning_interval(&self, time: &BigUint, distance: &BigUint) -> Option<(BigUint, BigUint)> {
+        let best = self.best_button_time(time);
+        if self.distance(&best, time) <= *distance {
+            return None;
+        }
+        let (mut lo, mut hi) = (BigUint::zero(), best.clone());
+        while lo < hi {
+            let mid: BigUint = (&lo + &hi) / 2u32;
+            if self.distance(&mid, time) > *distance {
+                hi = mid;
+            } else {
+                lo = mid + 1u32;
+            }
+        }
+        let first = lo;
+        let (mut lo, mut hi) = (best, time.clone());
+        while lo < hi {
+            let mid: BigUint = (&lo + &hi + 1u32) / 2u32;
+            if self.distance(&mid, time) > *distance {
+                lo = mid;
+            } else {
+                hi = mid - 1u32;
+            }
+        }
+        Some((first, lo))
+    }
+
+    fn count_wins(&self, time: &BigUint, distance: &BigUint) -> BigUint {
+        if *self == RaceModel::default() {
+            return count_wins(time, distance);
+        }
+        match self.winning_interval(time, distance) {
+            Some((first, last)) => last + 1u32 - first,
+            None => BigUint::zero(),
+        }
+    }
+}
+
+/// Counts the button times `b` with `b * (time - b) > distance`. These lie strictly between
+/// the roots of `b^2 - time*b + distance`, and are symmetric about `time / 2`.
+fn count_wins(time: &BigUint, distance: &BigUint) -> BigUint {
+    let square = time * time;
+    let four_distance = distance * 4u32;
+    if square <= four_distance {
+        return BigUint::zero();
+    }
+    let disc = square - four_distance;
+    let half = time / 2u32;
+    // The integer square root puts `lo` within a step of the lower root.
+    let mut lo = (time - disc.sqrt()) / 2u32;
+    while lo <= half && race_distance(&lo, time) <= *distance {
+        lo += 1u32;
+    }
+    if lo > half {
+        return BigUint::zero();
+    }
+    while !lo.is_zero() && race_distance(&(&lo - 1u32), time) > *distance {
+        lo -= 1u32;
+    }
+    time + 1u32 - lo * 2u32
+}
+
+fn print_interval(model: &RaceModel, time: &BigUint, distance: &BigUint) {
+    match model.winning_interval(time, distance) {
+        Some((first, last)) => println!("{time} {distance}: {first}..={last}"),
+        None => println!("{time} {distance}: none"),
+    }
+}
+
+fn part1(model: &RaceModel, intervals: bool) {
     let file = File::open("day6.txt").unwrap();
     let mut lines = BufReader::new(file).lines();
     let times = parse_int_list(&lines.next().unwrap().unwrap());
@@ -26,32 +157,128 @@ fn part1() {
     let races = iter::zip(times, distances);
     let mut race_ways = Vec::new();
     for (time, distance) in races {
-        let mut ways = 0;
-        for b in 0..=time {
-            if race_distance(b, time) > distance {
-                ways += 1
-            }
+        let ways = model.count_wins(&time, &distance);
+        if intervals {
+            print_interval(model, &time, &distance);
         }
         race_ways.push(ways);
     }
-    println!("{}", race_ways.iter().fold(1, |acc, w| acc * w));
+    println!(
+        "{}",
+        race_ways.iter().fold(BigUint::one(), |acc, w| acc * w)
+    );
 }
 
-fn part2() {
+fn part2(model: &RaceModel, intervals: bool) {
     let file = File::open("day6.txt").unwrap();
     let mut lines = BufReader::new(file).lines();
     let time = parse_wide_int(&lines.next().unwrap().unwrap());
     let distance = parse_wide_int(&lines.next().unwrap().unwrap());
-    let mut ways = 0;
-    for b in 0..=time {
-        if race_distance(b, time) > distance {
-            ways += 1
-        }
+    let ways = model.count_wins(&time, &distance);
+    if intervals {
+        print_interval(model, &time, &distance);
     }
     println!("{ways}");
 }
 
 fn main() {
-    part1();
-    part2();
+    let mut model = RaceModel::default();
+    let mut intervals = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || -> BigUint {
+            args.next()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| panic!("{arg} needs a number"))
+        };
+        match arg.as_str() {
+            "--accel" => model.accel = value(),
+            "--max-speed" => model.max_speed = Some(value()),
+            "--penalty" => model.penalty = value(),
+            "--intervals" => intervals = true,
+            _ => panic!("unknown argument {arg:?}"),
+        }
+    }
+    part1(&model, intervals);
+    part2(&model, intervals);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts winning button times by trying every one.
+    fn count_wins_by_loop(model: &RaceModel, time: &BigUint, distance: &BigUint) -> BigUint {
+        let mut ways = BigUint::zero();
+        let mut b = BigUint::zero();
+        while b <= *time {
+            if model.distance(&b, time) > *distance {
+                ways += 1u32;
+            }
+            b += 1u32;
+        }
+        ways
+    }
+
+    fn check_model(model: &RaceModel, times: u32) {
+        for time in 0..times {
+            for distance in 0..=time * time / 4 + 1 {
+                let (time, distance) = (BigUint::from(time), BigUint::from(distance));
+                assert_eq!(
+                    model.count_wins(&time, &distance),
+                    count_wins_by_loop(model, &time, &distance),
+                    "{model:?}, time {time}, distance {distance}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn closed_form_matches_loop() {
+        let plain = RaceModel::default();
+        for time in 0..60u32 {
+            for distance in 0..=time * time / 4 + 1 {
+                let (time, distance) = (BigUint::from(time), BigUint::from(distance));
+                assert_eq!(
+                    count_wins(&time, &distance),
+                    count_wins_by_loop(&plain, &time, &distance),
+                    "time {time}, distance {distance}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn models_match_loop() {
+        let model = |accel: u32, max_speed: Option<u32>, penalty: u32| RaceModel {
+            accel: accel.into(),
+            max_speed: max_speed.map(BigUint::from),
+            penalty: penalty.into(),
+        };
+        for m in [
+            model(2, None, 0),
+            model(1, Some(5), 0),
+            model(1, None, 3),
+            model(3, Some(10), 2),
+            model(0, None, 0),
+        ] {
+            check_model(&m, 40);
+        }
+    }
+
+    #[test]
+    fn example() {
+        let model = RaceModel::default();
+        let races = [(7u32, 9u32, 4u32), (15, 40, 8), (30, 200, 9)];
+        for (time, distance, ways) in races {
+            assert_eq!(
+                model.count_wins(&time.into(), &distance.into()),
+                BigUint::from(ways)
+            );
+        }
+        assert_eq!(
+            model.count_wins(&71530u32.into(), &940200u32.into()),
+            BigUint::from(71503u32)
+        );
+    }
 }
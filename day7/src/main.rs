@@ -1,149 +1,339 @@
-use std::cmp::{self, Ordering};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-
-#[derive(Debug, Eq, PartialEq)]
-struct Card(char, bool);
-
-impl Card {
-    fn strength(&self) -> u32 {
-        match self.0 {
-            'A' => 14,
-            'K' => 13,
-            'Q' => 12,
-            'J' => {
-                if self.1 {
-                    1
-                } else {
-                    11
+use std::env;
+use std::error::Error;
+use std::fs;
+
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Hand {
+    /// Index into the rule set's categories.
+    hand_type: usize,
+    /// Index of each card's rank in the rule set's ranks.
+    cards: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Category {
+    name: String,
+    /// Groups of same-ranked cards the hand must contain, largest first.
+    groups: Vec<u32>,
+    straight: bool,
+    flush: bool,
+}
+
+#[derive(Debug)]
+struct RuleSet {
+    name: String,
+    /// Card ranks from weakest to strongest.
+    ranks: Vec<char>,
+    /// Ranks that stand in for whatever card makes the best hand, but still break ties as
+    /// themselves.
+    wild: Vec<char>,
+    /// Whether each card is written as its rank followed by its suit.
+    suits: bool,
+    hand_size: usize,
+    /// Hand categories from weakest to strongest.
+    categories: Vec<Category>,
+}
+
+const STANDARD_RULES: &str = "\
+name standard
+ranks 23456789TJQKA
+size 5
+category high-card 1
+category one-pair 2
+category two-pair 2 2
+category three-of-a-kind 3
+category full-house 3 2
+category four-of-a-kind 4
+category five-of-a-kind 5
+";
+
+const JOKER_RULES: &str = "\
+name jokers
+ranks J23456789TQKA
+wild J
+size 5
+category high-card 1
+category one-pair 2
+category two-pair 2 2
+category three-of-a-kind 3
+category full-house 3 2
+category four-of-a-kind 4
+category five-of-a-kind 5
+";
+
+/// Parses lines of the form `name <name>`, `ranks <weakest..strongest>`, `wild <ranks>`,
+/// `size <cards>`, `suits` and `category <name> [group sizes..] [straight] [flush]`, with
+/// categories listed weakest first.
+fn parse_rules(s: &str) -> Result<RuleSet, String> {
+    let mut rules = RuleSet {
+        name: String::new(),
+        ranks: Vec::new(),
+        wild: Vec::new(),
+        suits: false,
+        hand_size: 5,
+        categories: Vec::new(),
+    };
+    for (i, line) in s.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            ["name", name] => rules.name = name.to_string(),
+            ["ranks", ranks] => rules.ranks = ranks.chars().collect(),
+            ["wild", wild] => rules.wild = wild.chars().collect(),
+            ["size", size] => {
+                rules.hand_size = size.parse().map_err(|e| format!("line {}: {}", i + 1, e))?
+            }
+            ["suits"] => rules.suits = true,
+            ["category", name, ref requirements @ ..] => {
+                let mut category = Category {
+                    name: name.to_string(),
+                    groups: Vec::new(),
+                    straight: false,
+                    flush: false,
+                };
+                for &r in requirements {
+                    match r {
+                        "straight" => category.straight = true,
+                        "flush" => category.flush = true,
+                        n => category
+                            .groups
+                            .push(n.parse().map_err(|e| format!("line {}: {}", i + 1, e))?),
+                    }
+                }
+                category.groups.sort_by(|a, b| b.cmp(a));
+                if rules.categories.iter().any(|c| c.name == category.name) {
+                    return Err(format!("line {}: category {} repeated", i + 1, name));
                 }
+                rules.categories.push(category);
             }
-            'T' => 10,
-            d => d.to_digit(10).expect("card should be a digit"),
+            _ => return Err(format!("line {}: bad rule {:?}", i + 1, line)),
         }
     }
+    if rules.ranks.is_empty() || rules.categories.is_empty() {
+        return Err("rules need ranks and at least one category".to_string());
+    }
+    if let Some(w) = rules.wild.iter().find(|w| !rules.ranks.contains(w)) {
+        return Err(format!("wild card {} is not one of the ranks", w));
+    }
+    Ok(rules)
 }
 
-impl PartialOrd for Card {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl Category {
+    /// Whether wild cards can be chosen so the hand meets this category. `groups` holds the
+    /// sizes of the groups of non-wild cards, largest first, and `ranks` and `suits` their
+    /// distinct ranks and suits.
+    fn matches(
+        &self,
+        rules: &RuleSet,
+        groups: &[u32],
+        ranks: &[usize],
+        suits: &[char],
+        wilds: u32,
+    ) -> bool {
+        let missing: u32 = self
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(i, &need)| need.saturating_sub(groups.get(i).copied().unwrap_or(0)))
+            .sum();
+        if missing > wilds {
+            return false;
+        }
+        if self.straight {
+            let distinct = groups.iter().all(|&g| g == 1);
+            let span = match (ranks.iter().min(), ranks.iter().max()) {
+                (Some(lo), Some(hi)) => hi - lo + 1,
+                _ => 0,
+            };
+            if !distinct || span > rules.hand_size || rules.ranks.len() < rules.hand_size {
+                return false;
+            }
+        }
+        if self.flush && (!rules.suits || suits.len() > 1) {
+            return false;
+        }
+        true
     }
 }
 
-impl Ord for Card {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.strength().cmp(&other.strength())
+fn classify(rules: &RuleSet, cards: &[(usize, char)]) -> Option<usize> {
+    let mut ranks = Vec::with_capacity(cards.len());
+    let mut suits = Vec::new();
+    let mut wilds = 0;
+    for &(rank, suit) in cards {
+        if rules.wild.contains(&rules.ranks[rank]) {
+            wilds += 1;
+            continue;
+        }
+        ranks.push(rank);
+        if !suits.contains(&suit) {
+            suits.push(suit);
+        }
     }
+    ranks.sort_unstable();
+    let mut groups: Vec<u32> = ranks
+        .chunk_by(|a, b| a == b)
+        .map(|g| g.len() as u32)
+        .collect();
+    groups.sort_unstable_by(|a, b| b.cmp(a));
+    ranks.dedup();
+    (0..rules.categories.len())
+        .rev()
+        .find(|&i| rules.categories[i].matches(rules, &groups, &ranks, &suits, wilds))
 }
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
-struct Hand {
-    hand_type: HandType,
-    cards: [Card; 5],
+/// Reads each hand's cards, as written, and its bid.
+fn parse_bids(s: &str) -> Result<Vec<(&str, u32)>, String> {
+    let mut bids = Vec::new();
+    for line in s.lines() {
+        let (cards, bid) = line
+            .split_once(' ')
+            .ok_or_else(|| format!("{:?} has no bid", line))?;
+        let bid = bid
+            .parse()
+            .map_err(|e| format!("{:?} has a bad bid: {}", line, e))?;
+        bids.push((cards, bid));
+    }
+    Ok(bids)
+}
+
+fn parse_hand(hand: &str, rules: &RuleSet) -> Result<Hand, String> {
+    let mut chars = hand.chars();
+    let mut cards = Vec::new();
+    while let Some(label) = chars.next() {
+        let rank = rules
+            .ranks
+            .iter()
+            .position(|&r| r == label)
+            .ok_or_else(|| format!("{:?} has unknown card {:?}", hand, label))?;
+        let suit = if rules.suits {
+            chars
+                .next()
+                .ok_or_else(|| format!("{:?} has a card without a suit", hand))?
+        } else {
+            ' '
+        };
+        cards.push((rank, suit));
+    }
+    if cards.len() != rules.hand_size {
+        return Err(format!(
+            "{:?} has {} cards instead of {}",
+            hand,
+            cards.len(),
+            rules.hand_size
+        ));
+    }
+    let hand_type = classify(rules, &cards)
+        .ok_or_else(|| format!("{:?} fits no category of {}", hand, rules.name))?;
+    let cards = cards.iter().map(|&(rank, _)| rank).collect();
+    Ok(Hand { hand_type, cards })
 }
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
-enum HandType {
-    HighCard,
-    OnePair,
-    TwoPair,
-    ThreeOfAKind,
-    FullHouse,
-    FourOfAKind,
-    FiveOfAKind,
-}
-
-fn type_from_groups(groups: &Vec<u32>) -> HandType {
-    match groups.len() {
-        1 => HandType::FiveOfAKind,
-        2 => {
-            if groups.contains(&4) {
-                HandType::FourOfAKind
-            } else {
-                HandType::FullHouse
-            }
+struct Entry<'a> {
+    hand: Hand,
+    bid: u32,
+    /// The cards as written in the input.
+    cards: &'a str,
+}
+
+/// Every hand in the tournament under `rules`, weakest first.
+fn rank_hands<'a>(rules: &RuleSet, bids: &[(&'a str, u32)]) -> Result<Vec<Entry<'a>>, String> {
+    let mut ranked = Vec::with_capacity(bids.len());
+    for &(cards, bid) in bids {
+        ranked.push(Entry {
+            hand: parse_hand(cards, rules)?,
+            bid,
+            cards,
+        });
+    }
+    ranked.sort();
+    Ok(ranked)
+}
+
+/// Warns about equal hands, whose relative rank (and so winnings) the rules leave undecided.
+fn report_ties(rules: &RuleSet, ranked: &[Entry]) {
+    for pair in ranked.windows(2) {
+        if pair[0].hand == pair[1].hand {
+            eprintln!(
+                "{} (bid {}) and {} (bid {}) tie under the {} rules",
+                pair[0].cards, pair[0].bid, pair[1].cards, pair[1].bid, rules.name
+            );
         }
-        3 => {
-            if groups.contains(&3) {
-                HandType::ThreeOfAKind
-            } else {
-                HandType::TwoPair
-            }
+    }
+}
+
+/// What the hand at 0-based position `i` in the ranking wins, or an error on overflow.
+fn winnings(e: &Entry, i: usize) -> Result<u64, String> {
+    (i as u64 + 1)
+        .checked_mul(u64::from(e.bid))
+        .ok_or_else(|| format!("winnings for {} overflowed", e.cards))
+}
+
+fn print_table(rules: &RuleSet, ranked: &[Entry]) -> Result<(), String> {
+    let hand_width = ranked.iter().map(|e| e.cards.len()).max().unwrap_or(0);
+    let type_width = rules
+        .categories
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:>6} {:<hand_width$} {:<type_width$} {:>6} {:>10}",
+        "rank", "hand", "type", "bid", "winnings"
+    );
+    for (i, e) in ranked.iter().enumerate() {
+        println!(
+            "{:>6} {:<hand_width$} {:<type_width$} {:>6} {:>10}",
+            i + 1,
+            e.cards,
+            rules.categories[e.hand.hand_type].name,
+            e.bid,
+            winnings(e, i)?
+        );
+    }
+    Ok(())
+}
+
+fn total_winnings(ranked: &[Entry]) -> Result<u64, String> {
+    let mut total: u64 = 0;
+    for (i, e) in ranked.iter().enumerate() {
+        total = total
+            .checked_add(winnings(e, i)?)
+            .ok_or("total winnings overflowed")?;
+    }
+    Ok(total)
+}
+
+fn play(rules: &RuleSet, bids: &[(&str, u32)], table: bool) -> Result<u64, String> {
+    let ranked = rank_hands(rules, bids)?;
+    report_ties(rules, &ranked);
+    if table {
+        print_table(rules, &ranked)?;
+    }
+    total_winnings(&ranked)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut table = false;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--table" => table = true,
+            _ => paths.push(arg),
         }
-        4 => HandType::OnePair,
-        5 => HandType::HighCard,
-        _ => panic!(),
-    }
-}
-
-fn make_groups(cards: [char; 5], replace_joker: char) -> HashMap<char, u32> {
-    let mut groups: HashMap<char, u32> = HashMap::new();
-    for card in cards {
-        let card = if card == 'J' { replace_joker } else { card };
-        groups.entry(card).and_modify(|x| *x += 1).or_insert(1);
-    }
-    groups
-}
-
-fn parse_line(line: &str, jokers: bool) -> (Hand, u32) {
-    let mut iter = line.chars();
-    let a = iter.next().unwrap();
-    let b = iter.next().unwrap();
-    let c = iter.next().unwrap();
-    let d = iter.next().unwrap();
-    let e = iter.next().unwrap();
-    assert!(iter.next() == Some(' '));
-    let bid = iter.as_str().parse().unwrap();
-
-    let cards = [a, b, c, d, e];
-    let hand_type = if jokers {
-        let mut best = HandType::HighCard;
-        for replace in ['2', '3', '4', '5', '6', '7', '8', '9', 'T', 'Q', 'K', 'A'] {
-            let groups = make_groups(cards, replace);
-            let hand_type = type_from_groups(&groups.values().copied().collect());
-            best = cmp::max(best, hand_type);
+    }
+
+    let f = fs::read_to_string("day7.txt")?;
+    let bids = parse_bids(&f)?;
+    if paths.is_empty() {
+        for rules in [STANDARD_RULES, JOKER_RULES] {
+            println!("{}", play(&parse_rules(rules)?, &bids, table)?);
         }
-        best
-    } else {
-        let groups = make_groups(cards, 'J');
-        type_from_groups(&groups.values().copied().collect())
-    };
-    let cards = [
-        Card(a, jokers),
-        Card(b, jokers),
-        Card(c, jokers),
-        Card(d, jokers),
-        Card(e, jokers),
-    ];
-    (Hand { hand_type, cards }, bid)
-}
-
-fn part1() {
-    let file = File::open("day7.txt").unwrap();
-    let lines = BufReader::new(file).lines();
-    let mut hand_bids: Vec<(Hand, u32)> = lines.map(|l| parse_line(&l.unwrap(), false)).collect();
-    hand_bids.sort();
-    let mut winnings = 0;
-    for i in 0..hand_bids.len() {
-        winnings += hand_bids[i].1 * (i as u32 + 1);
-    }
-    println!("{}", winnings);
-}
-
-fn part2() {
-    let file = File::open("day7.txt").unwrap();
-    let lines = BufReader::new(file).lines();
-    let mut hand_bids: Vec<(Hand, u32)> = lines.map(|l| parse_line(&l.unwrap(), true)).collect();
-    hand_bids.sort();
-    let mut winnings = 0;
-    for i in 0..hand_bids.len() {
-        winnings += hand_bids[i].1 * (i as u32 + 1);
-    }
-    println!("{}", winnings);
-}
-
-fn main() {
-    part1();
-    part2();
+    }
+    for path in paths {
+        let rules = parse_rules(&fs::read_to_string(&path)?)?;
+        println!("{} {}", rules.name, play(&rules, &bids, table)?);
+    }
+    Ok(())
 }
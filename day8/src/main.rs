@@ -1,106 +1,339 @@
-use nom::branch::alt;
 use nom::bytes::complete::tag;
-use nom::character::complete::{alphanumeric1, char, line_ending, multispace1};
-use nom::combinator::value;
-use nom::multi::many1;
-use nom::sequence::tuple;
+use nom::character::complete::{alphanumeric1, anychar, line_ending, multispace1};
+use nom::combinator::map_opt;
+use nom::multi::{many1, separated_list1};
+use nom::sequence::{delimited, tuple};
 use nom::IResult;
-use num::integer::lcm;
-use std::collections::HashMap;
+use num::Integer;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs;
 
-#[derive(Clone, Debug)]
-enum Inst {
-    L,
-    R,
-}
+/// Which exit to take, as an index into each node's exits.
+#[derive(Clone, Copy, Debug)]
+struct Inst(usize);
 
-fn parse_inst(s: &str) -> IResult<&str, Inst> {
-    alt((value(Inst::L, char('L')), value(Inst::R, char('R'))))(s)
+/// Parses one instruction, given the letters naming each exit in order.
+fn parse_inst(alphabet: &str) -> impl Fn(&str) -> IResult<&str, Inst> + '_ {
+    move |s| map_opt(anychar, |c| alphabet.chars().position(|a| a == c).map(Inst))(s)
 }
 
 #[derive(Debug)]
 struct Entry<'a> {
     src: &'a str,
-    dsts: (&'a str, &'a str),
+    dsts: Vec<&'a str>,
 }
 
-fn parse_entry(s: &str) -> IResult<&str, Entry> {
-    let (s, (src, _, dst1, _, dst2, _, _)) = tuple((
-        alphanumeric1,
-        tag(" = ("),
-        alphanumeric1,
-        tag(", "),
+fn parse_entry(s: &str) -> IResult<&str, Entry<'_>> {
+    let (s, (src, _, dsts, _)) = tuple((
         alphanumeric1,
-        tag(")"),
+        tag(" = "),
+        delimited(
+            tag("("),
+            separated_list1(tag(", "), alphanumeric1),
+            tag(")"),
+        ),
         line_ending,
     ))(s)?;
-    Ok((
-        s,
-        Entry {
-            src,
-            dsts: (dst1, dst2),
-        },
-    ))
+    Ok((s, Entry { src, dsts }))
 }
 
-fn parse_input(s: &str) -> IResult<&str, (Vec<Inst>, Vec<Entry>)> {
-    let (s, (insts, _, entries)) = tuple((many1(parse_inst), multispace1, many1(parse_entry)))(s)?;
+fn parse_input<'a>(s: &'a str, alphabet: &str) -> IResult<&'a str, (Vec<Inst>, Vec<Entry<'a>>)> {
+    let (s, (insts, _, entries)) =
+        tuple((many1(parse_inst(alphabet)), multispace1, many1(parse_entry)))(s)?;
     Ok((s, (insts, entries)))
 }
 
-fn main() {
-    let f = fs::read_to_string("day8.txt").unwrap();
-    let (remaining, (insts, entries)) = parse_input(&f).unwrap();
-    assert!(remaining.is_empty());
-    let mut network = HashMap::new();
-    for entry in entries {
-        network.insert(entry.src, entry.dsts);
+type Network<'a> = HashMap<&'a str, Vec<&'a str>>;
+
+/// Matches a node name against a pattern where `*` stands for any run of characters and `?`
+/// for any one character.
+fn matches(pattern: &str, node: &str) -> bool {
+    match pattern.chars().next() {
+        None => node.is_empty(),
+        Some('*') => (0..=node.len())
+            .filter(|&i| node.is_char_boundary(i))
+            .any(|i| matches(&pattern[1..], &node[i..])),
+        Some(p) => match node.chars().next() {
+            Some(n) if p == '?' || p == n => {
+                matches(&pattern[p.len_utf8()..], &node[n.len_utf8()..])
+            }
+            _ => false,
+        },
     }
-    let mut node = "AAA";
+}
+
+/// The steps at which a ghost stands on a goal node: each of `transient` once, then each of
+/// `periodic` plus any multiple of `period`.
+#[derive(Debug)]
+struct Arrivals {
+    transient: Vec<u64>,
+    periodic: Vec<u64>,
+    period: u64,
+    /// Steps before the ghost's path starts repeating.
+    pre_period: u64,
+}
+
+impl Arrivals {
+    fn at_end(&self, steps: u64) -> bool {
+        if steps < self.pre_period {
+            self.transient.contains(&steps)
+        } else {
+            let offset = (steps - self.pre_period) % self.period;
+            self.periodic.contains(&(self.pre_period + offset))
+        }
+    }
+}
+
+fn arrivals(network: &Network, insts: &[Inst], start: &str, goal: &str) -> Arrivals {
+    let mut cursor = start;
     let mut steps = 0;
-    for inst in insts.iter().cycle() {
-        if node == "ZZZ" {
-            break;
+    let mut seen: HashMap<(&str, usize), u64> = HashMap::new();
+    let mut hits = Vec::new();
+    loop {
+        let m = steps as usize % insts.len();
+        if let Some(&pre_period) = seen.get(&(cursor, m)) {
+            let (transient, periodic) = hits.iter().partition(|&&h| h < pre_period);
+            return Arrivals {
+                transient,
+                periodic,
+                period: steps - pre_period,
+                pre_period,
+            };
         }
-        let next = network.get(node).unwrap();
-        node = match inst {
-            Inst::L => next.0,
-            Inst::R => next.1,
-        };
+        seen.insert((cursor, m), steps);
+        if matches(goal, cursor) {
+            hits.push(steps);
+        }
+        cursor = network.get(cursor).unwrap()[insts[m].0];
         steps += 1;
     }
-    println!("{steps}");
+}
 
-    // From experimenting by hand, input seems to be carefully crafted so that the
-    // number of iterations until the first "Z" exit for each start node is *exactly*
-    // the cycle length.
-    // Otherwise this would be a lot more complicated.
-    let mut cycles: Vec<u64> = Vec::new();
-    for node in network.keys() {
-        if !node.ends_with("A") {
-            continue;
+const OVERFLOW: &str = "the ghosts' combined period overflowed";
+
+/// Combines `x = a (mod m)` with `x = b (mod n)`, if they agree anywhere.
+fn crt((a, m): (i128, i128), (b, n): (i128, i128)) -> Result<Option<(i128, i128)>, String> {
+    let e = m.extended_gcd(&n);
+    if (b - a) % e.gcd != 0 {
+        return Ok(None);
+    }
+    let step = n / e.gcd;
+    let lcm = (m / e.gcd).checked_mul(n).ok_or(OVERFLOW)?;
+    let k = ((b - a) / e.gcd % step).checked_mul(e.x).ok_or(OVERFLOW)? % step;
+    let x = k
+        .checked_mul(m)
+        .and_then(|km| km.checked_add(a))
+        .ok_or(OVERFLOW)?
+        .rem_euclid(lcm);
+    Ok(Some((x, lcm)))
+}
+
+/// The first step at which every ghost stands on a goal node at once, or `None` if they
+/// never do.
+fn first_simultaneous(ghosts: &[Arrivals]) -> Result<Option<u64>, String> {
+    let Some(settled) = ghosts.iter().map(|g| g.pre_period).max() else {
+        return Ok(None);
+    };
+    if let Some(steps) = (0..settled).find(|&s| ghosts.iter().all(|g| g.at_end(s))) {
+        return Ok(Some(steps));
+    }
+    // From `settled` on every ghost is periodic, so try each combination of periodic hits.
+    let mut combined = vec![(0i128, 1i128)];
+    for g in ghosts {
+        let mut next = Vec::new();
+        for &c in combined.iter() {
+            for &h in g.periodic.iter() {
+                next.extend(crt(c, (i128::from(h), i128::from(g.period)))?);
+            }
         }
-        let mut cursor: &str = node;
-        let mut steps = 0;
-        let mut seen: HashMap<(&str, u64), u64> = HashMap::new();
-        for inst in insts.iter().cycle() {
-            let m = steps % insts.len() as u64;
-            if seen.contains_key(&(cursor, m)) {
-                let start = *seen.get(&(cursor, m)).unwrap();
-                let cycle: u64 = steps - start;
-                cycles.push(cycle);
-                break;
+        combined = next;
+    }
+    let settled = i128::from(settled);
+    let mut first: Option<i128> = None;
+    for (x, lcm) in combined {
+        let steps = if x >= settled {
+            x
+        } else {
+            ((settled - x + lcm - 1) / lcm)
+                .checked_mul(lcm)
+                .and_then(|d| d.checked_add(x))
+                .ok_or(OVERFLOW)?
+        };
+        first = Some(first.map_or(steps, |f| f.min(steps)));
+    }
+    first
+        .map(|steps| {
+            u64::try_from(steps).map_err(|_| format!("step {} does not fit in u64", steps))
+        })
+        .transpose()
+}
+
+#[derive(Debug)]
+enum Problem<'a> {
+    Undefined {
+        node: &'a str,
+        used_by: &'a str,
+    },
+    Duplicate {
+        node: &'a str,
+    },
+    WrongExits {
+        node: &'a str,
+        exits: usize,
+        expected: usize,
+    },
+    NoMatch {
+        pattern: &'a str,
+    },
+    NeverReaches {
+        start: &'a str,
+        goal: &'a str,
+    },
+}
+
+impl fmt::Display for Problem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Undefined { node, used_by } => {
+                write!(f, "{} leads to {}, which is never defined", used_by, node)
             }
-            seen.insert((cursor, m), steps);
-            let next = network.get(cursor).unwrap();
-            cursor = match inst {
-                Inst::L => next.0,
-                Inst::R => next.1,
-            };
-            steps += 1;
+            Problem::Duplicate { node } => write!(f, "{} is defined more than once", node),
+            Problem::WrongExits {
+                node,
+                exits,
+                expected,
+            } => write!(f, "{} has {} exits instead of {}", node, exits, expected),
+            Problem::NoMatch { pattern } => write!(f, "no node matches {}", pattern),
+            Problem::NeverReaches { start, goal } => {
+                write!(f, "{} never reaches a node matching {}", start, goal)
+            }
+        }
+    }
+}
+
+impl Problem<'_> {
+    /// Whether navigation can still go ahead despite this problem.
+    fn is_warning(&self) -> bool {
+        matches!(self, Problem::Duplicate { .. })
+    }
+}
+
+fn starts<'a>(network: &Network<'a>, pattern: &str) -> Vec<&'a str> {
+    let mut starts: Vec<&str> = network
+        .keys()
+        .copied()
+        .filter(|node| matches(pattern, node))
+        .collect();
+    starts.sort();
+    starts
+}
+
+/// Checks the network and walks each route's ghosts. Returns the problems found and, unless
+/// there were errors, each route's ghosts' arrivals.
+fn validate<'a>(
+    insts: &[Inst],
+    alphabet: &str,
+    entries: &[Entry<'a>],
+    network: &Network<'a>,
+    routes: &[(&'a str, &'a str)],
+) -> (Vec<Problem<'a>>, Vec<Vec<Arrivals>>) {
+    let mut problems = Vec::new();
+    let mut defined = HashSet::new();
+    for entry in entries {
+        if !defined.insert(entry.src) {
+            problems.push(Problem::Duplicate { node: entry.src });
+        }
+        if entry.dsts.len() != alphabet.chars().count() {
+            problems.push(Problem::WrongExits {
+                node: entry.src,
+                exits: entry.dsts.len(),
+                expected: alphabet.chars().count(),
+            });
+        }
+        for &dst in entry.dsts.iter() {
+            if !network.contains_key(dst) {
+                problems.push(Problem::Undefined {
+                    node: dst,
+                    used_by: entry.src,
+                });
+            }
+        }
+    }
+    for &(start, goal) in routes {
+        for pattern in [start, goal] {
+            if starts(network, pattern).is_empty() {
+                problems.push(Problem::NoMatch { pattern });
+            }
+        }
+    }
+    if !problems.iter().all(Problem::is_warning) {
+        // Walking the network would run into the undefined nodes or missing exits.
+        return (problems, Vec::new());
+    }
+
+    let mut ghosts = Vec::new();
+    for &(start, goal) in routes {
+        let mut route = Vec::new();
+        for node in starts(network, start) {
+            let a = arrivals(network, insts, node, goal);
+            if a.transient.is_empty() && a.periodic.is_empty() {
+                problems.push(Problem::NeverReaches { start: node, goal });
+            }
+            route.push(a);
+        }
+        ghosts.push(route);
+    }
+    (problems, ghosts)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut alphabet = "LR".to_string();
+    let mut routes: Vec<(String, String)> = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--alphabet" => alphabet = args.next().ok_or("--alphabet needs letters")?,
+            "--route" => {
+                let start = args.next().ok_or("--route needs a start pattern")?;
+                let goal = args.next().ok_or("--route needs a goal pattern")?;
+                routes.push((start, goal));
+            }
+            _ => return Err(format!("unknown argument {:?}", arg).into()),
         }
     }
-    let lcm: u64 = cycles.iter().fold(1, |acc, &c| lcm(acc, c));
-    println!("{lcm}");
+    if routes.is_empty() {
+        routes.push(("AAA".to_string(), "ZZZ".to_string()));
+        routes.push(("*A".to_string(), "*Z".to_string()));
+    }
+    let routes: Vec<(&str, &str)> = routes
+        .iter()
+        .map(|(start, goal)| (start.as_str(), goal.as_str()))
+        .collect();
+
+    let f = fs::read_to_string("day8.txt")?;
+    let (remaining, (insts, entries)) = parse_input(&f, &alphabet).map_err(|e| e.to_owned())?;
+    if !remaining.is_empty() {
+        return Err(format!("trailing input {:?}", remaining).into());
+    }
+    let mut network = HashMap::new();
+    for entry in entries.iter() {
+        network.insert(entry.src, entry.dsts.clone());
+    }
+    let (problems, ghosts) = validate(&insts, &alphabet, &entries, &network, &routes);
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+    if !problems.iter().all(Problem::is_warning) {
+        return Err(format!("{} problems with the network", problems.len()).into());
+    }
+
+    for ((start, goal), ghosts) in routes.into_iter().zip(ghosts) {
+        let steps = first_simultaneous(&ghosts)?
+            .ok_or_else(|| format!("the ghosts from {} never all reach {} at once", start, goal))?;
+        println!("{steps}");
+    }
+    Ok(())
 }
@@ -1,52 +1,194 @@
+use num::{BigInt, BigRational, One, Zero};
+use std::env;
+use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
-fn delta(vs: &Vec<i32>) -> Vec<i32> {
-    let mut ds = Vec::new();
-    for i in 0..vs.len() - 1 {
-        ds.push(vs[i + 1] - vs[i]);
+/// A history fitted by its difference table, kept as the first entry of each row (its
+/// Newton forward differences) down to the last row that isn't all zeroes.
+struct Polynomial {
+    diffs: Vec<BigInt>,
+}
+
+impl Polynomial {
+    /// Fits the history, or returns `None` if its differences only reach zero once they run
+    /// out, so it's no polynomial of degree below `values.len() - 1`.
+    fn fit(values: &[BigInt]) -> Option<Polynomial> {
+        let mut diffs = Vec::new();
+        let mut row = values.to_vec();
+        while !row.iter().all(Zero::is_zero) {
+            diffs.push(row[0].clone());
+            row = row.windows(2).map(|w| &w[1] - &w[0]).collect();
+        }
+        if row.is_empty() && !values.is_empty() {
+            return None;
+        }
+        Some(Polynomial { diffs })
+    }
+
+    fn degree(&self) -> usize {
+        self.diffs.len().saturating_sub(1)
+    }
+
+    /// The value at position `x`, where the history covers positions `0..n`. Sums
+    /// `diffs[j] * binomial(x, j)`, which is exact for negative `x` too.
+    fn value_at(&self, x: i64) -> BigInt {
+        let x = BigInt::from(x);
+        let mut value = BigInt::zero();
+        let mut binomial = BigInt::from(1);
+        for (j, d) in self.diffs.iter().enumerate() {
+            if j > 0 {
+                binomial = binomial * (&x - (j - 1)) / j;
+            }
+            value += d * &binomial;
+        }
+        value
     }
-    ds
 }
 
-fn is_zeroes(vs: &Vec<i32>) -> bool {
-    vs.iter().all(|&v| v == 0)
+/// The least-squares polynomial of a given degree through a history, for histories that
+/// aren't exactly polynomial.
+struct BestFit {
+    /// Coefficients of `x^0`, `x^1`, ...
+    coeffs: Vec<BigRational>,
 }
 
-fn extrapolate(vs: &Vec<i32>) -> i32 {
-    if is_zeroes(&vs) {
-        0
-    } else {
-        let ds = delta(&vs);
-        let next_d = extrapolate(&ds);
-        vs.last().unwrap() + next_d
+impl BestFit {
+    /// Solves the normal equations exactly; needs more values than `degree`.
+    fn fit(values: &[BigInt], degree: usize) -> BestFit {
+        let n = degree + 1;
+        let powers = |x: usize| -> Vec<BigRational> {
+            let x = BigRational::from_integer(BigInt::from(x));
+            let mut p = vec![BigRational::one()];
+            for _ in 1..n {
+                let next = p.last().unwrap() * &x;
+                p.push(next);
+            }
+            p
+        };
+        // Augmented matrix [A^T A | A^T y].
+        let mut m = vec![vec![BigRational::zero(); n + 1]; n];
+        for (x, y) in values.iter().enumerate() {
+            let p = powers(x);
+            let y = BigRational::from_integer(y.clone());
+            for i in 0..n {
+                for j in 0..n {
+                    m[i][j] += &p[i] * &p[j];
+                }
+                m[i][n] += &p[i] * &y;
+            }
+        }
+        for col in 0..n {
+            let pivot = (col..n).find(|&r| !m[r][col].is_zero()).unwrap();
+            m.swap(col, pivot);
+            let pivot_row = m[col].clone();
+            for (r, row) in m.iter_mut().enumerate() {
+                if r != col && !row[col].is_zero() {
+                    let factor = &row[col] / &pivot_row[col];
+                    for (a, b) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                        *a -= &factor * b;
+                    }
+                }
+            }
+        }
+        let coeffs = (0..n).map(|i| &m[i][n] / &m[i][i]).collect();
+        BestFit { coeffs }
+    }
+
+    /// The value at position `x`, rounded to the nearest integer.
+    fn value_at(&self, x: i64) -> BigInt {
+        let x = BigRational::from_integer(BigInt::from(x));
+        let mut value = BigRational::zero();
+        for c in self.coeffs.iter().rev() {
+            value = value * &x + c;
+        }
+        value.round().to_integer()
     }
 }
 
-fn extrapolate_back(vs: &Vec<i32>) -> i32 {
-    if is_zeroes(&vs) {
-        0
-    } else {
-        let ds = delta(&vs);
-        let prev_d = extrapolate_back(&ds);
-        vs.first().unwrap() - prev_d
+enum Model {
+    Exact(Polynomial),
+    BestFit(BestFit),
+}
+
+impl Model {
+    fn degree(&self) -> usize {
+        match self {
+            Model::Exact(p) => p.degree(),
+            Model::BestFit(f) => f.coeffs.len() - 1,
+        }
+    }
+
+    fn value_at(&self, x: i64) -> BigInt {
+        match self {
+            Model::Exact(p) => p.value_at(x),
+            Model::BestFit(f) => f.value_at(x),
+        }
     }
 }
 
-fn main() {
-    let file = File::open("day9.txt").unwrap();
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut forward: i64 = 1;
+    let mut back: i64 = 1;
+    let mut degrees = false;
+    let mut fallback: Option<usize> = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--forward" => forward = args.next().ok_or("--forward needs a count")?.parse()?,
+            "--back" => back = args.next().ok_or("--back needs a count")?.parse()?,
+            "--degrees" => degrees = true,
+            "--fallback" => {
+                fallback = Some(args.next().ok_or("--fallback needs a degree")?.parse()?)
+            }
+            _ => return Err(format!("unknown argument {:?}", arg).into()),
+        }
+    }
+
+    let file = File::open("day9.txt")?;
     let lines = BufReader::new(file).lines();
-    let mut sum_next = 0;
-    let mut sum_prev = 0;
-    for line in lines {
-        let values: Vec<i32> = line
-            .unwrap()
+    let mut sum_next = BigInt::zero();
+    let mut sum_prev = BigInt::zero();
+    let mut fitted = Vec::new();
+    let mut not_polynomial = 0;
+    for (i, line) in lines.enumerate() {
+        let values: Vec<BigInt> = line?
             .split(' ')
-            .map(|v| v.parse().unwrap())
-            .collect();
-        sum_next += extrapolate(&values);
-        sum_prev += extrapolate_back(&values);
+            .map(|v| v.parse())
+            .collect::<Result<_, _>>()?;
+        let model = match (Polynomial::fit(&values), fallback) {
+            (Some(poly), _) => Model::Exact(poly),
+            (None, Some(degree)) if degree < values.len() => {
+                eprintln!(
+                    "line {}: not polynomial, using a best fit of degree {}",
+                    i + 1,
+                    degree
+                );
+                Model::BestFit(BestFit::fit(&values, degree))
+            }
+            (None, _) => {
+                eprintln!(
+                    "line {}: not a polynomial of degree below {}",
+                    i + 1,
+                    values.len() - 1
+                );
+                not_polynomial += 1;
+                continue;
+            }
+        };
+        sum_next += model.value_at(values.len() as i64 - 1 + forward);
+        sum_prev += model.value_at(-back);
+        fitted.push((i, model));
+    }
+    if not_polynomial > 0 {
+        return Err(format!("{} histories are not polynomial", not_polynomial).into());
     }
     println!("{sum_next}");
     println!("{sum_prev}");
+    if degrees {
+        for (i, model) in fitted.iter() {
+            println!("line {}: degree {}", i + 1, model.degree());
+        }
+    }
+    Ok(())
 }